@@ -1,14 +1,17 @@
 use std::collections::HashSet;
+use std::convert::TryFrom;
 
 use itertools::Itertools;
-use postgres::{types::ToSql, Transaction, Client};
-use rocket::http::HeaderMap;
-use crate::schema::{Schema, Table};
+use postgres::{types::ToSql, Transaction, Client, GenericClient};
+use rocket::http::{CookieJar, HeaderMap};
+use chrono::{DateTime, Utc};
+use crate::schema::{leaf_paths, Column, ColumnDefault, ExtraFields, Index, RequestParameter, RouteParameter, Schema, Source, Table};
 use std::fmt::Display;
 use std::error::Error;
-use crate::types::{ConversionError, header_to_sql};
+use crate::types::{ConversionError, ConversionOptions, Type, unwrap_if_required, Uuid};
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum DbError {
     PostgresError(postgres::Error),
     ConversionError(String, ConversionError),
@@ -33,17 +36,63 @@ impl From<postgres::Error> for DbError {
     }
 }
 
-pub fn insert_event(table: &Table, conn: &mut Transaction, json: &serde_json::Value, headers: &HeaderMap) -> Result<(), DbError> {
+/// Information about the request that an event was sent in, which columns can be populated from.
+pub struct RequestMetadata<'a> {
+    pub app_id: &'a str,
+    pub headers: &'a HeaderMap<'a>,
+    /// The decoded URL query parameters, in order.
+    pub query: Vec<(&'a str, &'a str)>,
+    pub cookies: &'a CookieJar<'a>,
+    pub key_label: Option<&'a str>,
+    pub received_at: DateTime<Utc>,
+    /// When the client sent the request, according to its own clock.
+    pub sent_at: Option<&'a serde_json::Value>,
+    pub request_id: Uuid,
+    pub schema_version: &'a str,
+}
+
+pub fn insert_event(table: &Table, conn: &mut Transaction, json: &serde_json::Value, batch_index: usize, request: &RequestMetadata)
+    -> Result<(), DbError>
+{
     let query = format!(r#"INSERT INTO "{}" ({}) VALUES ({})"#,
                         table.name,
                         table.columns.iter().map(|column| format!(r#""{}""#, column.name)).join(", "),
                         (1..=table.columns.len()).map(|idx| format!("${}", idx)).join(", "));
+    let extra_fields = table.extra_fields(json);
+    if table.extra_fields == ExtraFields::Reject && !extra_fields.is_empty() {
+        let keys = leaf_paths(&extra_fields);
+        return Err(DbError::ConversionError(keys[0].to_string(), ConversionError::UnknownFields(keys)));
+    }
+    for rule in &table.rules {
+        rule.check(table, json).map_err(|(key, err)| DbError::ConversionError(key, err))?;
+    }
+    let clock_skew = table.clock_skew(request.sent_at, request.received_at)
+        .map_err(|err| DbError::ConversionError("sent_at".to_string(), err))?;
+    let extra_fields = if extra_fields.is_empty() { serde_json::Value::Null } else { serde_json::Value::Object(extra_fields) };
+
     let mut values = Vec::<Box<dyn ToSql + Sync>>::with_capacity(table.columns.len());
     for column in &table.columns {
-        let value = match &column.header {
-            Some(header) => header_to_sql(&column.name, headers.get(&header).next(), column.required),
-            None => column.type_.json_to_sql(&column.name, &json[&column.name], column.required),
-        }.map_err(|err| DbError::ConversionError(column.name.to_string(), err))?;
+        // A column that keeps the original value of a corrected one reads the same field.
+        let json_column = table.json_column(column);
+        let request_value = column.request_parameter().map(|parameter| request_value(column, parameter, request));
+        let is_missing = match (&column.source, &request_value) {
+            (Some(Source::KeyLabel), _) => request.key_label.is_none(),
+            (Some(Source::ExtraFields), _) => extra_fields.is_null(),
+            (Some(_), _) => false,
+            (None, Some(value)) => value.is_null(),
+            (None, None) => json_column.json_value(json).is_null(),
+        };
+        let value = match (&column.default, &column.source, &request_value) {
+            (Some(default), _, _) if is_missing => default_to_sql(column, default, table, request),
+            (_, Some(source), _) => source_to_sql(column, *source, table, &extra_fields, batch_index, request),
+            (_, None, Some(value)) =>
+                column.type_.json_to_sql(&column.name, value, &ConversionOptions { coerce: true, ..column.conversion_options(table) }),
+            (_, None, None) => {
+                let clock_skew = clock_skew.filter(|_| column.correct_skew);
+                let options = ConversionOptions { required: column.required, clock_skew, ..json_column.conversion_options(table) };
+                column.type_.json_to_sql(json_column.json_path(), json_column.json_value(json), &options)
+            }
+        }.map_err(|err| DbError::ConversionError(json_column.json_path().to_string(), err))?;
         values.push(value);
     }
     // println!("{} {:?}", query, values);
@@ -51,7 +100,173 @@ pub fn insert_event(table: &Table, conn: &mut Transaction, json: &serde_json::Va
     Ok(())
 }
 
-pub fn create_tables(schema: &Schema, conn: &mut Client) -> Result<(), DbError> {
+/// The value of a column that is populated from the request, as a JSON string, or an array of them
+/// for array columns. Parameters that occur more than once only fill array columns completely.
+fn request_value(column: &Column, parameter: RequestParameter, request: &RequestMetadata) -> serde_json::Value {
+    let values: Vec<&str> = match parameter {
+        RequestParameter::Header(name) => request.headers.get(name).collect(),
+        RequestParameter::Query(name) => request.query.iter()
+            .filter(|(key, _)| *key == name)
+            .map(|(_, value)| *value)
+            .collect(),
+        RequestParameter::Cookie(name) => request.cookies.get(name).map(|cookie| cookie.value()).into_iter().collect(),
+        RequestParameter::Route(RouteParameter::AppId) => vec![request.app_id],
+    };
+    let mut values = values.into_iter().map(|value| serde_json::Value::String(value.to_string()));
+    match column.type_ {
+        Type::Array(_) => Some(serde_json::Value::Array(values.collect())).filter(|array| array != &serde_json::json!([])),
+        _ => values.next(),
+    }.unwrap_or(serde_json::Value::Null)
+}
+
+fn source_to_sql<'a>(column: &Column, source: Source, table: &'a Table, extra_fields: &serde_json::Value, batch_index: usize,
+                     request: &RequestMetadata<'a>)
+    -> Result<Box<dyn ToSql + Sync + 'a>, ConversionError>
+{
+    match source {
+        Source::KeyLabel => unwrap_if_required(&column.name, request.key_label, column.required),
+        Source::ExtraFields => column.type_.json_to_sql(&column.name, extra_fields, &column.conversion_options(table)),
+        Source::ReceivedAt => Ok(Box::new(request.received_at)),
+        Source::AppId => Ok(Box::new(request.app_id)),
+        Source::Table => Ok(Box::new(table.name.as_str())),
+        Source::RequestId => Ok(Box::new(request.request_id)),
+        Source::BatchIndex => {
+            let index = i32::try_from(batch_index).map_err(|_| ConversionError::IntegerOutOfRange {
+                key: column.name.to_string(),
+                value: batch_index.to_string(),
+                type_: column.type_.name(),
+            })?;
+            Ok(Box::new(index))
+        }
+        Source::SchemaVersion => Ok(Box::new(request.schema_version)),
+    }
+}
+
+fn default_to_sql(column: &Column, default: &ColumnDefault, table: &Table, request: &RequestMetadata)
+    -> Result<Box<dyn ToSql + Sync>, ConversionError>
+{
+    match default {
+        ColumnDefault::Literal(literal) => column.type_.json_to_sql(&column.name, literal, &column.conversion_options(table)),
+        ColumnDefault::Now => Ok(Box::new(Utc::now())),
+        ColumnDefault::ServerTime => Ok(Box::new(request.received_at)),
+        ColumnDefault::AppId => Ok(Box::new(request.app_id.to_string())),
+    }
+}
+
+/// Determines which differences between the schema and the database `create_tables` resolves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MigrationMode {
+    /// Only create missing tables and indexes; report any other mismatch as an error.
+    Off,
+    /// Add missing columns and drop NOT NULL constraints; changes that never lose data.
+    Additive,
+    /// Additionally change column types and drop columns that are not in the schema.
+    Destructive,
+}
+
+/// A difference between a table in the schema and the corresponding table in the database.
+#[derive(Debug)]
+pub enum Difference<'a> {
+    MissingTable { table: &'a Table },
+    MissingColumn { table: &'a Table, column: &'a Column },
+    ExtraColumn { table: &'a Table, name: String, required: bool },
+    WrongType { table: &'a Table, column: &'a Column, postgres_type: String },
+    NotNullable { table: &'a Table, column: &'a Column },
+    Nullable { table: &'a Table, column: &'a Column },
+    MissingIndex { table: &'a Table, index: &'a Index },
+    WrongIndex { table: &'a Table, index: &'a Index, columns: Vec<String>, method: String },
+}
+
+impl Display for Difference<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            Difference::MissingTable { table } =>
+                write!(f, "table \"{}\" does not exist", table.name),
+            Difference::MissingColumn { table, column } =>
+                write!(f, "table \"{}\" is missing column \"{}\" configured in the schema", table.name, column.name),
+            Difference::ExtraColumn { table, name, required: true } =>
+                write!(f, "table \"{}\" has an extra required column \"{}\" that is not in the schema", table.name, name),
+            Difference::ExtraColumn { table, name, required: false } =>
+                write!(f, "table \"{}\" has an extra column \"{}\" that is not in the schema", table.name, name),
+            Difference::WrongType { table, column, postgres_type } =>
+                write!(f, "table \"{}\" has column \"{}\" of type \"{}\", which does not match type \"{}\" configured in the schema",
+                       table.name, column.name, postgres_type, column.type_.postgres_type_name()),
+            Difference::NotNullable { table, column } =>
+                write!(f, "table \"{}\" has non-nullable column \"{}\" which is not required in the schema", table.name, column.name),
+            Difference::Nullable { table, column } =>
+                write!(f, "table \"{}\" has nullable column \"{}\" which is required in the schema", table.name, column.name),
+            Difference::MissingIndex { table, index } =>
+                write!(f, "table \"{}\" is missing index \"{}\" configured in the schema", table.name, index.name),
+            Difference::WrongIndex { table, index, columns, method } =>
+                write!(f, "table \"{}\" has index \"{}\" on ({}) using {}, which does not match the index on ({}) using {} configured in the schema",
+                       table.name, index.name, columns.join(", "), method, index.columns.join(", "), index.method.postgres_name()),
+        }
+    }
+}
+
+impl Difference<'_> {
+    /// Returns the SQL statements that resolve this difference, or `None` if that is not allowed in
+    /// the given migration mode.
+    pub fn migration(&self, migration_mode: MigrationMode) -> Option<String> {
+        match self {
+            Difference::MissingTable { table } =>
+                Some(creation_query(table)),
+            // A NOT NULL column without a default cannot be added to a table that has rows, so it
+            // is added as nullable, which leaves a `Nullable` difference for the operator.
+            Difference::MissingColumn { table, column } if column.required && default_expression(table, column).is_none() &&
+                    migration_mode >= MigrationMode::Additive =>
+                Some(format!(r#"ALTER TABLE "{}" ADD COLUMN {}"#, table.name, column_definition(table, &Column { required: false, ..(*column).clone() }))),
+            Difference::MissingColumn { table, column } if migration_mode >= MigrationMode::Additive =>
+                Some(format!(r#"ALTER TABLE "{}" ADD COLUMN {}"#, table.name, column_definition(table, column))),
+            Difference::ExtraColumn { table, name, .. } if migration_mode >= MigrationMode::Destructive =>
+                Some(format!(r#"ALTER TABLE "{}" DROP COLUMN "{}""#, table.name, name)),
+            Difference::ExtraColumn { table, name, required: true } if migration_mode >= MigrationMode::Additive =>
+                Some(format!(r#"ALTER TABLE "{}" ALTER COLUMN "{}" DROP NOT NULL"#, table.name, name)),
+            Difference::WrongType { table, column, .. } if migration_mode >= MigrationMode::Destructive =>
+                Some(format!(r#"ALTER TABLE "{}" ALTER COLUMN "{}" TYPE {} USING "{}"::{}"#,
+                             table.name, column.name, column.type_.postgres_type_name(), column.name, column.type_.postgres_type_name())),
+            Difference::NotNullable { table, column } if migration_mode >= MigrationMode::Additive =>
+                Some(format!(r#"ALTER TABLE "{}" ALTER COLUMN "{}" DROP NOT NULL"#, table.name, column.name)),
+            // This fails if the column contains NULLs, so it is only attempted when asked for.
+            Difference::Nullable { table, column } if migration_mode >= MigrationMode::Destructive =>
+                Some(format!(r#"ALTER TABLE "{}" ALTER COLUMN "{}" SET NOT NULL"#, table.name, column.name)),
+            Difference::MissingIndex { table, index } =>
+                Some(index_creation_query(table, index)),
+            Difference::WrongIndex { table, index, .. } if migration_mode >= MigrationMode::Destructive =>
+                Some(format!(r#"DROP INDEX "{}"; {}"#, index.name, index_creation_query(table, index))),
+            _ => None,
+        }
+    }
+
+    /// Whether the server works correctly if this difference is left unresolved.
+    pub fn is_harmless(&self) -> bool {
+        match self {
+            Difference::ExtraColumn { required, .. } => !required,
+            // Required values are checked before insertion anyway.
+            Difference::Nullable { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+/// Creates missing tables and indexes, and resolves differences between the schema and the existing
+/// tables as far as the migration mode allows. Any remaining harmful difference is an error.
+pub fn create_tables(schema: &Schema, conn: &mut Client, migration_mode: MigrationMode) -> Result<(), DbError> {
+    // Postgres DDL is transactional, so a failed migration leaves the database untouched.
+    let mut trans = conn.transaction()?;
+    for difference in diff_tables(schema, &mut trans)? {
+        match difference.migration(migration_mode) {
+            Some(migration) => trans.batch_execute(&migration)?,
+            None if difference.is_harmless() => {},
+            None => return Err(DbError::StructureError(difference.to_string())),
+        }
+    }
+    trans.commit()?;
+    Ok(())
+}
+
+/// Compares all tables in the schema with the database, in order of table name.
+pub fn diff_tables<'a>(schema: &'a Schema, conn: &mut impl GenericClient) -> Result<Vec<Difference<'a>>, DbError> {
     let existing_tables = conn.query(r#"
         SELECT relname
         FROM pg_catalog.pg_class
@@ -61,39 +276,71 @@ pub fn create_tables(schema: &Schema, conn: &mut Client) -> Result<(), DbError>
         .map(|row| row.get(0))
         .collect::<HashSet<String>>();
 
-    for table in schema.tables.values() {
+    let mut differences = Vec::new();
+    for table in schema.tables.values().sorted_by_key(|table| &table.name) {
         if !existing_tables.contains(&table.name) {
-            conn.execute(&creation_query(table), &[])?;
+            differences.push(Difference::MissingTable { table });
+            differences.extend(table.indexes.iter().map(|index| Difference::MissingIndex { table, index }));
         } else {
-            check_table(&table, conn)?;
+            differences.extend(diff_columns(table, conn)?);
+            differences.extend(diff_indexes(table, conn)?);
         }
     }
-    Ok(())
+    Ok(differences)
+}
+
+fn column_definition(table: &Table, column: &Column) -> String {
+    format!(
+        r#""{}" {}{}{}"#,
+        column.name,
+        column.type_.postgres_type_name(),
+        default_expression(table, column).map(|expression| format!(" default {}", expression)).unwrap_or_default(),
+        if column.required { " not null" } else { "" }
+    )
+}
+
+/// The DEFAULT of a column, so that rows inserted by other clients get it too. Defaults that only
+/// Attolytics knows, like the app ID, are left out.
+fn default_expression(table: &Table, column: &Column) -> Option<String> {
+    match column.default.as_ref()? {
+        // Literals are given in the format of the column, like seconds since the epoch.
+        ColumnDefault::Literal(literal) =>
+            Some(sql_literal(&column.type_.normalize(literal, &column.conversion_options(table)), &column.type_)),
+        ColumnDefault::Now | ColumnDefault::ServerTime => Some("now()".to_string()),
+        ColumnDefault::AppId => None,
+    }
+}
+
+/// Formats a JSON value as an SQL literal of the given type. Postgres casts quoted strings to the
+/// type of the column.
+fn sql_literal(json: &serde_json::Value, type_: &Type) -> String {
+    let quote = |string: &str| format!("'{}'", string.replace('\'', "''"));
+    match (json, type_) {
+        (serde_json::Value::Null, _) => "NULL".to_string(),
+        (serde_json::Value::Array(elements), Type::Array(element_type)) =>
+            format!("ARRAY[{}]::{}", elements.iter().map(|element| sql_literal(element, element_type)).join(", "), type_.postgres_type_name()),
+        (serde_json::Value::String(string), _) if *type_ != Type::Json => quote(string),
+        _ => quote(&json.to_string()),
+    }
 }
 
 fn creation_query(table: &Table) -> String {
     let columns = table.columns
         .iter()
-        .map(|column| format!(
-            r#"{} {}{}"#,
-            column.name,
-            column.type_.postgres_type_name(),
-            if column.required { " not null" } else { "" }
-        ))
+        .map(|column| column_definition(table, column))
         .join(", ");
-    format!(r#"
-        CREATE TABLE "{}" ({})
-        "#, table.name, columns)
+    format!(r#"CREATE TABLE "{}" ({})"#, table.name, columns)
 }
 
-fn check_table(table: &Table, conn: &mut Client) -> Result<(), DbError> {
+fn diff_columns<'a>(table: &'a Table, conn: &mut impl GenericClient) -> Result<Vec<Difference<'a>>, DbError> {
     // https://stackoverflow.com/questions/20194806/how-to-get-a-list-column-names-and-datatype-of-a-table-in-postgresql
     let existing_columns = conn.query(r#"
         SELECT
             a.attname as "name",
             a.atttypid as "type_oid",
             pg_catalog.format_type(a.atttypid, a.atttypmod) as "postgres_type",
-            a.attnotnull and not a.atthasdef as "required"
+            a.attnotnull as "not_null",
+            a.atthasdef as "has_default"
         FROM
             pg_catalog.pg_attribute a
         WHERE
@@ -107,42 +354,175 @@ fn check_table(table: &Table, conn: &mut Client) -> Result<(), DbError> {
                     AND pg_catalog.pg_table_is_visible(c.oid)
             )
         "#, &[&table.name])?;
+    let mut differences = Vec::new();
     for existing_column in &existing_columns {
         let name: String = existing_column.get("name");
         let type_oid: postgres::types::Oid = existing_column.get("type_oid");
         let postgres_type: String = existing_column.get("postgres_type");
-        let required: bool = existing_column.get("required");
+        let not_null: bool = existing_column.get("not_null");
+        let has_default: bool = existing_column.get("has_default");
 
-        let column = table.columns.iter().find(|column| column.name == name);
+        let column = table.column(&name);
         match column {
             Some(column) => {
-                if type_oid != column.type_.postgres_type().oid() {
-                    return Err(DbError::StructureError(format!(
-                        "table \"{}\" has column \"{}\" of type \"{}\", which does not match type \"{}\" configured in the schema",
-                        table.name, name, postgres_type, column.type_.postgres_type_name())))
+                if type_oid != column.type_.postgres_type().oid() || !column.type_.matches_format_type(&postgres_type) {
+                    differences.push(Difference::WrongType { table, column, postgres_type });
+                }
+                // Missing values get the schema default, so they are never NULL.
+                if not_null && !column.required && !(has_default && column.default.is_some()) {
+                    differences.push(Difference::NotNullable { table, column });
                 }
-                if required && !column.required {
-                    return Err(DbError::StructureError(format!(
-                        "table \"{}\" has non-nullable column \"{}\" which is not required in the schema",
-                        table.name, name)))
+                if !not_null && column.required {
+                    differences.push(Difference::Nullable { table, column });
                 }
             }
             None => {
-                if required {
-                    return Err(DbError::StructureError(format!(
-                        "table \"{}\" has an extra required column \"{}\" that is not in the schema",
-                        table.name, name)).into())
-                }
+                // Inserts that leave out the column only fail if there is no default.
+                let required = not_null && !has_default;
+                differences.push(Difference::ExtraColumn { table, name, required });
             }
         }
     }
     for column in &table.columns {
         let matching_column = existing_columns.iter().find(|c| c.get::<&str, String>("name") == column.name);
         if matching_column.is_none() {
-            return Err(DbError::StructureError(format!(
-                "table \"{}\" is missing column \"{}\" configured in the schema",
-                table.name, column.name)));
+            differences.push(Difference::MissingColumn { table, column });
         }
     }
-    Ok(())
+    Ok(differences)
+}
+
+fn index_creation_query(table: &Table, index: &Index) -> String {
+    let columns = index.columns
+        .iter()
+        .map(|column| format!(r#""{}""#, column))
+        .join(", ");
+    format!(r#"CREATE INDEX "{}" ON "{}" USING {} ({})"#,
+            index.name, table.name, index.method.postgres_name(), columns)
+}
+
+/// Finds the indexes configured in the schema that do not exist yet. An existing index matches if
+/// it has the same method and columns, regardless of its name.
+fn diff_indexes<'a>(table: &'a Table, conn: &mut impl GenericClient) -> Result<Vec<Difference<'a>>, DbError> {
+    let existing_indexes = conn.query(r#"
+        SELECT
+            i.relname::text as "name",
+            am.amname::text as "method",
+            array(
+                SELECT a.attname::text
+                FROM unnest(x.indkey::int2[]) WITH ORDINALITY AS k(attnum, ord)
+                    JOIN pg_catalog.pg_attribute a ON a.attrelid = x.indrelid AND a.attnum = k.attnum
+                ORDER BY k.ord
+            ) as "columns"
+        FROM
+            pg_catalog.pg_index x
+            JOIN pg_catalog.pg_class i ON i.oid = x.indexrelid
+            JOIN pg_catalog.pg_class t ON t.oid = x.indrelid
+            JOIN pg_catalog.pg_am am ON am.oid = i.relam
+        WHERE t.relname = $1
+            AND pg_catalog.pg_table_is_visible(t.oid)
+        "#, &[&table.name])?;
+    let mut differences = Vec::new();
+    for index in &table.indexes {
+        let matches = existing_indexes.iter().any(|existing_index| {
+            existing_index.get::<&str, String>("method") == index.method.postgres_name() &&
+                existing_index.get::<&str, Vec<String>>("columns") == index.columns
+        });
+        if matches {
+            continue;
+        }
+        match existing_indexes.iter().find(|i| i.get::<&str, String>("name") == index.name) {
+            Some(existing_index) => differences.push(Difference::WrongIndex {
+                table,
+                index,
+                columns: existing_index.get("columns"),
+                method: existing_index.get("method"),
+            }),
+            None => differences.push(Difference::MissingIndex { table, index }),
+        }
+    }
+    Ok(differences)
+}
+
+#[test]
+fn migrations_depend_on_mode() {
+    let schema = Schema::from_yaml(r#"
+        tables:
+          events:
+            columns:
+              - name: score
+                type: i32
+              - name: level
+                type: i32
+                required: true
+        apps: {}
+        "#).unwrap();
+    let table = &schema.tables["events"];
+    let column = &table.columns[0];
+
+    let missing_column = Difference::MissingColumn { table, column };
+    assert_eq!(missing_column.migration(MigrationMode::Off), None);
+    assert_eq!(missing_column.migration(MigrationMode::Additive).unwrap(),
+               r#"ALTER TABLE "events" ADD COLUMN "score" int4"#);
+    let missing_required_column = Difference::MissingColumn { table, column: &table.columns[1] };
+    assert_eq!(missing_required_column.migration(MigrationMode::Additive).unwrap(),
+               r#"ALTER TABLE "events" ADD COLUMN "level" int4"#);
+
+    let wrong_type = Difference::WrongType { table, column, postgres_type: "bigint".to_string() };
+    assert_eq!(wrong_type.migration(MigrationMode::Additive), None);
+    assert_eq!(wrong_type.migration(MigrationMode::Destructive).unwrap(),
+               r#"ALTER TABLE "events" ALTER COLUMN "score" TYPE int4 USING "score"::int4"#);
+
+    let extra_column = Difference::ExtraColumn { table, name: "junk".to_string(), required: false };
+    assert!(extra_column.is_harmless());
+    assert_eq!(extra_column.migration(MigrationMode::Additive), None);
+}
+
+#[test]
+fn column_definitions_include_defaults() {
+    let schema = Schema::from_yaml(r#"
+        tables:
+          events:
+            columns:
+              - name: time
+                type: timestamp
+                default: server_time
+              - name: platform
+                default: "it's unknown"
+                required: true
+              - name: app
+                default: app_id
+              - name: tags
+                type: [string]
+                default: [a, null]
+              - name: properties
+                type: json
+                default: {"a": "b"}
+              - name: installed
+                type: timestamp
+                format: epoch_ms
+                default: 1554130180500
+        apps: {}
+        "#).unwrap();
+    let table = &schema.tables["events"];
+    let definitions = table.columns.iter().map(|column| column_definition(table, column)).collect::<Vec<_>>();
+    assert_eq!(definitions, vec![
+        r#""time" timestamptz default now()"#,
+        r#""platform" varchar default 'it''s unknown' not null"#,
+        r#""app" varchar"#,
+        r#""tags" varchar[] default ARRAY['a', NULL]::varchar[]"#,
+        r#""properties" jsonb default '{"a":"b"}'"#,
+        r#""installed" timestamptz default '2019-04-01T14:49:40.500+00:00'"#,
+    ]);
+
+    let invalid = Schema::from_yaml(r#"
+        tables:
+          events:
+            columns:
+              - name: score
+                type: i32
+                default: high
+        apps: {}
+        "#);
+    assert!(invalid.is_err());
 }
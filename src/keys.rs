@@ -0,0 +1,165 @@
+use std::collections::{HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::sync::Mutex;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+const SALT_LENGTH: usize = 16;
+
+/// A salted SHA-256 hash of a secret key, written as `<salt>$<hash>` with both parts base64-encoded.
+/// Secret keys are long random strings, so a fast hash is sufficient; it only needs to prevent the
+/// keys from being read from the schema file.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct KeyHash {
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+}
+
+impl KeyHash {
+    pub fn generate(key: &str) -> KeyHash {
+        let mut salt = vec![0; SALT_LENGTH];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let hash = KeyHash::hash(&salt, key);
+        KeyHash { salt, hash }
+    }
+
+    pub fn matches(&self, key: &str) -> bool {
+        KeyHash::hash(&self.salt, key).ct_eq(&self.hash).into()
+    }
+
+    fn hash(salt: &[u8], key: &str) -> Vec<u8> {
+        Sha256::new()
+            .chain_update(salt)
+            .chain_update(key.as_bytes())
+            .finalize()
+            .to_vec()
+    }
+}
+
+impl TryFrom<String> for KeyHash {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<KeyHash, String> {
+        let (salt, hash) = value.split_once('$')
+            .ok_or_else(|| "key hash should be of the form <salt>$<hash>".to_string())?;
+        let salt = BASE64.decode(salt)
+            .map_err(|err| format!("invalid salt in key hash: {}", err))?;
+        let hash = BASE64.decode(hash)
+            .map_err(|err| format!("invalid hash in key hash: {}", err))?;
+        if hash.len() != Sha256::output_size() {
+            return Err(format!("key hash should be {} bytes long, but was {}", Sha256::output_size(), hash.len()));
+        }
+        Ok(KeyHash { salt, hash })
+    }
+}
+
+impl Display for KeyHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "{}${}", BASE64.encode(&self.salt), BASE64.encode(&self.hash))
+    }
+}
+
+/// Compares two strings in a time that depends only on their lengths, not on their contents.
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.as_bytes().ct_eq(b.as_bytes()).into()
+}
+
+/// Verifies a hex-encoded HMAC-SHA256 signature over the timestamp, nonce and body of a request,
+/// separated by newlines.
+pub fn verify_signature(secret: &str, timestamp: &str, nonce: &str, body: &str, signature: &str) -> bool {
+    let signature = match decode_hex(signature) {
+        Some(signature) => signature,
+        None => return false,
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b"\n");
+    mac.update(nonce.as_bytes());
+    mac.update(b"\n");
+    mac.update(body.as_bytes());
+    mac.verify_slice(&signature).is_ok()
+}
+
+pub fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    // from_str_radix also accepts a leading sign, which is not hex.
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// The nonces of signed requests that were seen recently, per app. A nonce only needs to be
+/// remembered until the timestamp it was signed with is no longer accepted; after that, the
+/// request is rejected for being too old anyway.
+#[derive(Debug, Default)]
+pub struct NonceCache(Mutex<NonceCacheEntries>);
+
+#[derive(Debug, Default)]
+pub struct NonceCacheEntries {
+    nonces: HashSet<(String, String)>,
+    /// In order of insertion, which is roughly in order of expiry.
+    expiries: VecDeque<(i64, (String, String))>,
+}
+
+impl NonceCache {
+    /// Records a nonce until the given expiry time (in seconds since the epoch). Returns false if
+    /// the nonce was already recorded for this app.
+    pub fn insert(&self, app_id: &str, nonce: &str, expires_at: i64, now: i64) -> bool {
+        let mut entries = self.0.lock().unwrap();
+        while entries.expiries.front().is_some_and(|(expiry, _)| *expiry <= now) {
+            let (_, key) = entries.expiries.pop_front().unwrap();
+            entries.nonces.remove(&key);
+        }
+        let key = (app_id.to_string(), nonce.to_string());
+        if !entries.nonces.insert(key.clone()) {
+            return false;
+        }
+        entries.expiries.push_back((expires_at, key));
+        true
+    }
+}
+
+#[test]
+fn verify_request_signature() {
+    // Computed with: printf '1700000000\nabc\n{"events":[]}' | openssl dgst -sha256 -hmac secret
+    let signature = "3fb22116d4c7d0721ec2aa0bc9972d8a3c686385de5aafa9326bd2cdea794620";
+    assert!(verify_signature("secret", "1700000000", "abc", r#"{"events":[]}"#, signature));
+    assert!(!verify_signature("secret", "1700000001", "abc", r#"{"events":[]}"#, signature));
+    assert!(!verify_signature("secret", "1700000000", "abd", r#"{"events":[]}"#, signature));
+    assert!(!verify_signature("secret", "1700000000", "abc", r#"{"events":[{}]}"#, signature));
+    assert!(!verify_signature("other", "1700000000", "abc", r#"{"events":[]}"#, signature));
+    assert!(!verify_signature("secret", "1700000000", "abc", r#"{"events":[]}"#, "not hex"));
+    assert!(!verify_signature("secret", "1700000000", "abc", r#"{"events":[]}"#, &signature.replacen("3f", "+f", 1)));
+    assert_eq!(decode_hex("+f"), None);
+}
+
+#[test]
+fn nonces_are_rejected_until_expired() {
+    let cache = NonceCache::default();
+    assert!(cache.insert("app", "abc", 100, 0));
+    assert!(!cache.insert("app", "abc", 100, 50));
+    assert!(cache.insert("other_app", "abc", 100, 50));
+    assert!(cache.insert("app", "abc", 200, 100));
+}
+
+#[test]
+fn key_hash_round_trip() {
+    let key_hash = KeyHash::generate("qD3eRda0709mD/3kGp4DlJtEQy5aMY0m");
+    let parsed = KeyHash::try_from(key_hash.to_string()).unwrap();
+    assert_eq!(parsed, key_hash);
+    assert!(parsed.matches("qD3eRda0709mD/3kGp4DlJtEQy5aMY0m"));
+    assert!(!parsed.matches("qD3eRda0709mD/3kGp4DlJtEQy5aMY0n"));
+    assert!(!parsed.matches(""));
+}
@@ -0,0 +1,102 @@
+use std::fs;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use postgres::NoTls;
+use r2d2::Pool;
+use r2d2_postgres::PostgresConnectionManager;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::task::spawn_blocking;
+use tokio::time::interval;
+
+use crate::db::{self, MigrationMode};
+use crate::schema::Schema;
+
+/// How often the schema file is checked for modifications.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The schema that requests are currently handled with. It can be replaced while the server is
+/// running; requests that are in flight at that time finish using the schema they started with.
+#[derive(Debug, Clone)]
+pub struct ActiveSchema(Arc<RwLock<Arc<Schema>>>);
+
+impl ActiveSchema {
+    pub fn new(schema: Schema) -> ActiveSchema {
+        ActiveSchema(Arc::new(RwLock::new(Arc::new(schema))))
+    }
+
+    pub fn get(&self) -> Arc<Schema> {
+        self.0.read().unwrap().clone()
+    }
+
+    fn set(&self, schema: Schema) {
+        *self.0.write().unwrap() = Arc::new(schema);
+    }
+}
+
+/// Reloads the schema file whenever the process receives SIGHUP, or the file's modification time
+/// changes. Never returns.
+///
+/// The new schema only becomes active if it parses, and the database tables could be created or
+/// migrated to match it. Otherwise, the error is logged and the old schema stays active. Server
+/// settings are not reloaded.
+pub async fn watch_schema(
+    file_name: String,
+    active_schema: ActiveSchema,
+    db_conn_pool: Pool<PostgresConnectionManager<NoTls>>,
+    migration_mode: MigrationMode)
+{
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => Some(hangup),
+        Err(err) => {
+            eprintln!("failed to listen for SIGHUP: {}", err);
+            None
+        }
+    };
+    let mut poll = interval(POLL_INTERVAL);
+    let mut modified = modification_time(&file_name);
+    loop {
+        tokio::select! {
+            Some(_) = async { hangup.as_mut()?.recv().await } => {},
+            _ = poll.tick() => {
+                if modification_time(&file_name) == modified {
+                    continue;
+                }
+            },
+        }
+        modified = modification_time(&file_name);
+        reload_schema(&file_name, &active_schema, &db_conn_pool, migration_mode).await;
+    }
+}
+
+fn modification_time(file_name: &str) -> Option<SystemTime> {
+    fs::metadata(file_name).and_then(|metadata| metadata.modified()).ok()
+}
+
+async fn reload_schema(
+    file_name: &str,
+    active_schema: &ActiveSchema,
+    db_conn_pool: &Pool<PostgresConnectionManager<NoTls>>,
+    migration_mode: MigrationMode)
+{
+    let file_name = file_name.to_string();
+    let db_conn_pool = db_conn_pool.clone();
+    // The Postgres client is blocking, so it must not run directly on an async worker thread.
+    let result = spawn_blocking(move || {
+        let schema = Schema::from_file(&file_name)
+            .map_err(|err| format!("failed to load schema file {}: {}", file_name, err))?;
+        let mut conn = db_conn_pool.get()
+            .map_err(|err| format!("failed to create database connection: {}", err))?;
+        db::create_tables(&schema, &mut conn, migration_mode)
+            .map_err(|err| format!("failed to update database tables: {}", err))?;
+        Ok::<_, String>((file_name, schema))
+    }).await;
+    match result {
+        Ok(Ok((file_name, schema))) => {
+            active_schema.set(schema);
+            println!("reloaded schema file {}", file_name);
+        }
+        Ok(Err(msg)) => eprintln!("{}; keeping the previous schema", msg),
+        Err(err) => eprintln!("failed to reload schema: {}; keeping the previous schema", err),
+    }
+}
@@ -1,57 +1,424 @@
-use std::convert::TryFrom;
+use std::borrow::Cow;
+use std::convert::{TryFrom, TryInto};
 
-use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeZone};
-use postgres::types::ToSql;
+use chrono::format::{Item, Parsed, StrftimeItems};
+use bytes::BytesMut;
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use postgres::types::{accepts, to_sql_checked, IsNull, Kind, ToSql};
+use postgres_protocol::types::{array_to_sql, ArrayDimension};
+use regex::Regex;
 use serde::Deserialize;
+use serde_json::{Number, Value};
 use std::fmt::Display;
 use std::error::Error;
 
-#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+use crate::keys::decode_hex;
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Default)]
+#[serde(try_from = "TypeName")]
 pub enum Type {
-    #[serde(rename = "bool")]
     Bool,
-    #[serde(rename = "i32")]
     I32,
-    #[serde(rename = "i64")]
     I64,
-    #[serde(rename = "f32")]
     F32,
-    #[serde(rename = "f64")]
     F64,
-    #[serde(rename = "string")]
+    #[default]
     String,
-    #[serde(rename = "timestamp")]
+    /// Like `String`, but without a length limit in Postgres.
+    Text,
     Timestamp,
+    Date,
+    Uuid,
+    /// Exact decimal number, with optional precision (total number of digits) and scale (number of
+    /// digits after the decimal point).
+    Numeric(Option<(u32, u32)>),
+    /// Any JSON value, stored as is.
+    Json,
+    /// A one-dimensional array of a non-array type.
+    Array(Box<Type>),
+}
+
+/// How a type is written in the schema: either its name, or a list with the name of the element
+/// type, like `[string]`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TypeName {
+    Scalar(String),
+    Array(Vec<String>),
+}
+
+/// Maximum precision of `numeric(p,s)` in Postgres.
+const MAX_NUMERIC_PRECISION: u32 = 1000;
+
+impl TryFrom<TypeName> for Type {
+    type Error = String;
+
+    fn try_from(value: TypeName) -> Result<Type, String> {
+        let element = match value {
+            TypeName::Scalar(name) => match name.strip_prefix('[').and_then(|name| name.strip_suffix(']')) {
+                Some(element) => element.to_string(),
+                None => return Type::try_from(name),
+            },
+            TypeName::Array(elements) => match <[String; 1]>::try_from(elements) {
+                Ok([element]) => element,
+                // Commas have to be quoted in YAML lists, e.g. ['numeric(10,2)'].
+                Err(_) => return Err("array types should list exactly one element type, like [string]".to_string()),
+            },
+        };
+        match Type::try_from(element)? {
+            Type::Array(_) => Err("arrays of arrays are not supported".to_string()),
+            element => Ok(Type::Array(Box::new(element))),
+        }
+    }
 }
 
-impl Default for Type {
-    fn default() -> Type {
-        Type::String
+impl TryFrom<String> for Type {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Type, String> {
+        match value.as_str() {
+            "bool" => Ok(Type::Bool),
+            "i32" => Ok(Type::I32),
+            "i64" => Ok(Type::I64),
+            "f32" => Ok(Type::F32),
+            "f64" => Ok(Type::F64),
+            "string" => Ok(Type::String),
+            "text" => Ok(Type::Text),
+            "timestamp" => Ok(Type::Timestamp),
+            "date" => Ok(Type::Date),
+            "uuid" => Ok(Type::Uuid),
+            "numeric" => Ok(Type::Numeric(None)),
+            "json" => Ok(Type::Json),
+            _ => parse_numeric_type(&value).map(|precision_scale| Type::Numeric(Some(precision_scale)))
+                .ok_or_else(|| format!("unknown column type \"{}\"", value)),
+        }
     }
 }
 
+/// Parses `numeric(p)` or `numeric(p,s)` into precision and scale.
+fn parse_numeric_type(type_: &str) -> Option<(u32, u32)> {
+    let arguments = type_.strip_prefix("numeric(")?.strip_suffix(')')?;
+    let (precision, scale) = arguments.split_once(',').unwrap_or((arguments, "0"));
+    let (precision, scale) = (precision.trim().parse().ok()?, scale.trim().parse().ok()?);
+    if precision == 0 || precision > MAX_NUMERIC_PRECISION || scale > precision {
+        return None;
+    }
+    Some((precision, scale))
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum ConversionError {
     MissingValue(String),
+    WrongType { key: String, expected: &'static str, actual: &'static str },
+    IntegerOutOfRange { key: String, value: String, type_: &'static str },
+    NonFiniteFloat { key: String, value: String, type_: &'static str },
+    TooLarge { key: String, size: usize, max_size: usize },
+    /// A string that is not valid for the type, such as a malformed UUID.
+    InvalidFormat { key: String, value: String, type_: &'static str },
+    UnknownFields(Vec<String>),
+    /// The value does not satisfy one of the `Rules` of the column.
+    RuleViolation { key: String, rule: &'static str, limit: String, value: String },
+    /// Fields that a `TableRule` requires are missing, or fields that it forbids are present.
+    TableRuleViolation { keys: Vec<String>, required: bool, condition: String },
     TimestampFormat(chrono::format::ParseError),
     TimestampTooLarge(),
+    /// The local time does not exist in the time zone, because it was skipped by a DST transition.
+    NonexistentLocalTime(NaiveDateTime),
+    /// The client clock differs from the server clock by more seconds than are corrected.
+    ClockSkew { key: String, skew: i64, max_skew: u64 },
 }
 
 impl Display for ConversionError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
         match self {
             ConversionError::MissingValue(key) => write!(f, "required value \"{}\" was omitted", key),
+            ConversionError::WrongType { key, expected, actual } =>
+                write!(f, "value \"{}\" should be {} but is {}", key, expected, actual),
+            ConversionError::IntegerOutOfRange { key, value, type_ } =>
+                write!(f, "value \"{}\" is out of range for {}: {}", key, type_, value),
+            ConversionError::NonFiniteFloat { key, value, type_ } =>
+                write!(f, "value \"{}\" is not finite as {}: {}", key, type_, value),
+            ConversionError::TooLarge { key, size, max_size } =>
+                write!(f, "value \"{}\" is {} bytes large, which exceeds the maximum of {} bytes", key, size, max_size),
+            ConversionError::InvalidFormat { key, value, type_ } =>
+                write!(f, "value \"{}\" is not a valid {}: {}", key, type_, value),
+            ConversionError::RuleViolation { key, rule, limit, value } =>
+                write!(f, "value \"{}\" violates rule {} {}: {}", key, rule, limit, value),
+            ConversionError::TableRuleViolation { keys, required, condition } =>
+                write!(f, "fields {} {} when {}",
+                       keys.iter().map(|key| format!("\"{}\"", key)).collect::<Vec<_>>().join(", "),
+                       if *required { "are required" } else { "are not allowed" },
+                       condition),
+            ConversionError::UnknownFields(keys) =>
+                write!(f, "unknown fields {}", keys.iter().map(|key| format!("\"{}\"", key)).collect::<Vec<_>>().join(", ")),
             ConversionError::TimestampFormat(err) => write!(f, "could not parse timestamp: {}", err),
             ConversionError::TimestampTooLarge() => write!(f, "could not parse timestame: value out of range"),
+            ConversionError::NonexistentLocalTime(naive) => write!(f, "local time {} does not exist in the server time zone", naive),
+            ConversionError::ClockSkew { key, skew, max_skew } =>
+                write!(f, "value \"{}\" differs from server time by {} seconds, more than the maximum of {}", key, skew, max_skew),
         }
     }
 }
 
 impl Error for ConversionError {}
 
+impl ConversionError {
+    /// Machine-readable error code that is reported to clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ConversionError::MissingValue(_) => "missing_value",
+            ConversionError::WrongType { .. } => "wrong_type",
+            ConversionError::IntegerOutOfRange { .. } | ConversionError::NonFiniteFloat { .. } => "out_of_range",
+            ConversionError::TooLarge { .. } => "too_large",
+            ConversionError::InvalidFormat { .. } => "invalid_format",
+            ConversionError::UnknownFields(_) => "unknown_field",
+            ConversionError::RuleViolation { .. } => "rule_violation",
+            ConversionError::TableRuleViolation { required: true, .. } => "missing_value",
+            ConversionError::TableRuleViolation { required: false, .. } => "forbidden_field",
+            ConversionError::TimestampFormat(_) | ConversionError::TimestampTooLarge() |
+                ConversionError::NonexistentLocalTime(_) => "bad_timestamp",
+            ConversionError::ClockSkew { .. } => "clock_skew",
+        }
+    }
+}
+
+/// The format that a timestamp column expects its values in.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum TimestampFormat {
+    /// Seconds since the Unix epoch, as a number.
+    EpochS,
+    /// Milliseconds since the Unix epoch, as a number.
+    EpochMs,
+    /// Microseconds since the Unix epoch, as a number.
+    EpochUs,
+    /// A string like `2019-04-01T14:49:40+02:00`.
+    Rfc3339,
+    /// A string like `Mon, 01 Apr 2019 14:49:40 +0200`.
+    Rfc2822,
+    /// A string in the given `strftime` format.
+    Strftime(String),
+}
+
+impl TryFrom<String> for TimestampFormat {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<TimestampFormat, String> {
+        match value.as_str() {
+            "epoch_s" => Ok(TimestampFormat::EpochS),
+            "epoch_ms" => Ok(TimestampFormat::EpochMs),
+            "epoch_us" => Ok(TimestampFormat::EpochUs),
+            "rfc3339" => Ok(TimestampFormat::Rfc3339),
+            "rfc2822" => Ok(TimestampFormat::Rfc2822),
+            _ if StrftimeItems::new(&value).any(|item| item == Item::Error) =>
+                Err(format!("invalid timestamp format \"{}\"", value)),
+            _ => Ok(TimestampFormat::Strftime(value)),
+        }
+    }
+}
+
+impl TimestampFormat {
+    fn units_per_second(&self) -> Option<i64> {
+        match self {
+            TimestampFormat::EpochS => Some(1),
+            TimestampFormat::EpochMs => Some(1_000),
+            TimestampFormat::EpochUs => Some(1_000_000),
+            _ => None,
+        }
+    }
+}
+
+/// The time zone that timestamps without a UTC offset are in.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(try_from = "String")]
+pub enum Zone {
+    #[default]
+    Utc,
+    /// The time zone of the server.
+    Local,
+    Fixed(FixedOffset),
+}
+
+impl TryFrom<String> for Zone {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Zone, String> {
+        match value.as_str() {
+            "UTC" | "utc" => Ok(Zone::Utc),
+            "local" => Ok(Zone::Local),
+            _ => value.parse().map(Zone::Fixed)
+                .map_err(|_| format!("time zone should be UTC, local or an offset like +02:00, but was \"{}\"", value)),
+        }
+    }
+}
+
+impl Zone {
+    fn localize(&self, naive: &NaiveDateTime) -> Result<DateTime<FixedOffset>, ConversionError> {
+        match self {
+            Zone::Utc => Ok(Utc.from_utc_datetime(naive).fixed_offset()),
+            // Of ambiguous times, pick the earlier one like Postgres does.
+            Zone::Local => Local.from_local_datetime(naive).earliest().map(|date_time| date_time.fixed_offset())
+                .ok_or(ConversionError::NonexistentLocalTime(*naive)),
+            Zone::Fixed(offset) => offset.from_local_datetime(naive).single()
+                .ok_or(ConversionError::TimestampTooLarge()),
+        }
+    }
+}
+
+/// Settings of a column that determine how its values are converted.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConversionOptions<'a> {
+    pub required: bool,
+    pub coerce: bool,
+    /// If not given, timestamps can be either seconds since the epoch or RFC 3339 strings.
+    pub format: Option<&'a TimestampFormat>,
+    /// If not given, timestamps without a UTC offset are rejected, unless the format lacks one.
+    pub time_zone: Option<Zone>,
+    /// For JSON values, the maximum size in bytes when serialized.
+    pub max_size: Option<usize>,
+    pub rules: Option<&'a Rules>,
+    /// For timestamps, the difference between the server clock and the client clock, which is
+    /// added to correct the values.
+    pub clock_skew: Option<Duration>,
+}
+
+/// Constraints on the values of a column beyond their type. For arrays, they apply to the elements.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct Rules {
+    /// For numbers, the smallest allowed value.
+    #[serde(default)]
+    pub min: Option<Number>,
+    /// For numbers, the largest allowed value.
+    #[serde(default)]
+    pub max: Option<Number>,
+    /// For strings, a regular expression that the whole value must match.
+    #[serde(default)]
+    pub pattern: Option<Pattern>,
+    /// The allowed values.
+    #[serde(default, rename = "enum")]
+    pub enum_: Option<Vec<Value>>,
+    /// For strings, the maximum number of characters.
+    #[serde(default)]
+    pub max_length: Option<usize>,
+}
+
+impl Rules {
+    pub fn is_empty(&self) -> bool {
+        *self == Rules::default()
+    }
+
+    /// Checks a value that has the right type. Allowed values are compared after `normalize`, so
+    /// that for example `1` and `1.0` are the same float.
+    fn check(&self, key: &str, json: &Value, normalize: impl Fn(&Value) -> Value) -> Result<(), ConversionError> {
+        let violation = |rule: &'static str, limit: String| ConversionError::RuleViolation {
+            key: key.to_string(),
+            rule,
+            limit,
+            value: json.to_string(),
+        };
+        // Numeric columns also accept numbers in strings.
+        let number = json.as_f64().or_else(|| json.as_str().and_then(|string| string.trim().parse().ok()));
+        if let (Some(min), Some(number)) = (&self.min, number) {
+            if number < min.as_f64().unwrap() {
+                return Err(violation("min", min.to_string()));
+            }
+        }
+        if let (Some(max), Some(number)) = (&self.max, number) {
+            if number > max.as_f64().unwrap() {
+                return Err(violation("max", max.to_string()));
+            }
+        }
+        if let (Some(pattern), Some(string)) = (&self.pattern, json.as_str()) {
+            if !pattern.regex.is_match(string) {
+                return Err(violation("pattern", pattern.pattern.to_string()));
+            }
+        }
+        if let (Some(max_length), Some(string)) = (self.max_length, json.as_str()) {
+            if string.chars().count() > max_length {
+                return Err(violation("max_length", max_length.to_string()));
+            }
+        }
+        if let Some(values) = &self.enum_ {
+            let value = normalize(json);
+            if !values.iter().any(|allowed| normalize(allowed) == value) {
+                return Err(violation("enum", Value::Array(values.clone()).to_string()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A regular expression that matches whole strings.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(try_from = "String")]
+pub struct Pattern {
+    pattern: String,
+    regex: Regex,
+}
+
+impl TryFrom<String> for Pattern {
+    type Error = String;
+
+    fn try_from(pattern: String) -> Result<Pattern, String> {
+        let regex = Regex::new(&format!("^(?:{})$", pattern))
+            .map_err(|err| format!("invalid pattern: {}", err))?;
+        Ok(Pattern { pattern, regex })
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Pattern) -> bool {
+        self.pattern == other.pattern
+    }
+}
+
+impl Eq for Pattern {}
+
 impl Type {
+    /// The name of the type in the schema.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Type::Bool => "bool",
+            Type::I32 => "i32",
+            Type::I64 => "i64",
+            Type::F32 => "f32",
+            Type::F64 => "f64",
+            Type::String => "string",
+            Type::Text => "text",
+            Type::Timestamp => "timestamp",
+            Type::Date => "date",
+            Type::Uuid => "uuid",
+            Type::Numeric(_) => "numeric",
+            Type::Json => "json",
+            Type::Array(_) => "array",
+        }
+    }
+
+    /// The type of the elements for arrays, or else the type itself.
+    pub fn element_type(&self) -> &Type {
+        match self {
+            Type::Array(element) => element,
+            _ => self,
+        }
+    }
+
+    /// The name of the type in DDL, including modifiers.
     pub fn postgres_type_name(&self) -> String {
-        self.postgres_type().name().to_string()
+        match self {
+            Type::Numeric(Some((precision, scale))) => format!("numeric({},{})", precision, scale),
+            Type::Array(element) => format!("{}[]", element.postgres_type_name()),
+            _ => self.postgres_type().name().to_string(),
+        }
+    }
+
+    /// Whether a column of this type is described as `format_type` by Postgres. Only needed for
+    /// types with modifiers; others are compared by OID.
+    pub fn matches_format_type(&self, format_type: &str) -> bool {
+        match self {
+            Type::Numeric(Some(_)) => format_type == self.postgres_type_name(),
+            Type::Array(element) => format_type.strip_suffix("[]").is_some_and(|format_type| element.matches_format_type(format_type)),
+            _ => true,
+        }
     }
 
     pub fn postgres_type(&self) -> postgres::types::Type {
@@ -62,25 +429,389 @@ impl Type {
             Type::F32 => postgres::types::Type::FLOAT4,
             Type::F64 => postgres::types::Type::FLOAT8,
             Type::String => postgres::types::Type::VARCHAR,
+            Type::Text => postgres::types::Type::TEXT,
             Type::Timestamp => postgres::types::Type::TIMESTAMPTZ,
+            Type::Date => postgres::types::Type::DATE,
+            Type::Uuid => postgres::types::Type::UUID,
+            Type::Numeric(_) => postgres::types::Type::NUMERIC,
+            Type::Json => postgres::types::Type::JSONB,
+            Type::Array(element) => match **element {
+                Type::Bool => postgres::types::Type::BOOL_ARRAY,
+                Type::I32 => postgres::types::Type::INT4_ARRAY,
+                Type::I64 => postgres::types::Type::INT8_ARRAY,
+                Type::F32 => postgres::types::Type::FLOAT4_ARRAY,
+                Type::F64 => postgres::types::Type::FLOAT8_ARRAY,
+                Type::String => postgres::types::Type::VARCHAR_ARRAY,
+                Type::Text => postgres::types::Type::TEXT_ARRAY,
+                Type::Timestamp => postgres::types::Type::TIMESTAMPTZ_ARRAY,
+                Type::Date => postgres::types::Type::DATE_ARRAY,
+                Type::Uuid => postgres::types::Type::UUID_ARRAY,
+                Type::Numeric(_) => postgres::types::Type::NUMERIC_ARRAY,
+                Type::Json => postgres::types::Type::JSONB_ARRAY,
+                Type::Array(_) => unreachable!("arrays of arrays are rejected when parsing the schema"),
+            },
         }
     }
 
-    pub fn json_to_sql(&self, key: &str, json: &serde_json::Value, required: bool) -> Result<Box<dyn ToSql + Sync>, ConversionError> {
-        match self {
-            Type::Bool => unwrap_if_required(key, json.as_bool(), required),
-            Type::I32 => unwrap_if_required(key, json.as_i64().map(|i| i32::try_from(i).ok()), required),
-            Type::I64 => unwrap_if_required(key, json.as_i64(), required),
-            Type::F32 => unwrap_if_required(key, json.as_f64().map(|f| f as f32), required),
-            Type::F64 => unwrap_if_required(key, json.as_f64(), required),
-            Type::String => unwrap_if_required(key, json.as_str().map(|s| s.to_string()), required),
-            Type::Timestamp => unwrap_if_required(key, json_to_date_time(json)?, required),
+    /// Converts a JSON value to SQL. `null` is treated like a missing value, while any other value
+    /// that does not match the type is an error, unless `coerce` is set and it can be converted.
+    pub fn json_to_sql(&self, key: &str, json: &Value, options: &ConversionOptions) -> Result<Box<dyn ToSql + Sync>, ConversionError> {
+        let json = if options.coerce { self.coerce(json, options) } else { Cow::Borrowed(json) };
+        let json = json.as_ref();
+        let required = options.required;
+        let value = match self {
+            Type::Bool => unwrap_if_required(key, self.json_to(key, json, Value::as_bool)?, required),
+            Type::I32 => unwrap_if_required(key, self.json_to_int::<i32>(key, json)?, required),
+            Type::I64 => unwrap_if_required(key, self.json_to_int::<i64>(key, json)?, required),
+            Type::F32 => {
+                let value = self.json_to(key, json, Value::as_f64)?.map(|f| f as f32);
+                if value.is_some_and(|f| !f.is_finite()) {
+                    return Err(ConversionError::NonFiniteFloat { key: key.to_string(), value: json.to_string(), type_: self.name() });
+                }
+                unwrap_if_required(key, value, required)
+            }
+            Type::F64 => unwrap_if_required(key, self.json_to(key, json, Value::as_f64)?, required),
+            Type::String | Type::Text => unwrap_if_required(key, self.json_to(key, json, |json| json.as_str().map(|s| s.to_string()))?, required),
+            Type::Date => {
+                let value = self.json_to(key, json, Value::as_str)?
+                    .map(|string| NaiveDate::parse_from_str(string, "%Y-%m-%d")
+                        .map_err(|_| self.invalid_format(key, json)))
+                    .transpose()?;
+                unwrap_if_required(key, value, required)
+            }
+            Type::Uuid => {
+                let value = self.json_to(key, json, Value::as_str)?
+                    .map(|string| Uuid::parse(string).ok_or_else(|| self.invalid_format(key, json)))
+                    .transpose()?;
+                unwrap_if_required(key, value, required)
+            }
+            Type::Numeric(precision_scale) => {
+                // Strings are allowed too, because JSON numbers may lose precision on the way.
+                let value = self.json_to(key, json, |json| match json {
+                    Value::Number(number) => Some(number.to_string()),
+                    Value::String(string) => Some(string.trim().to_string()),
+                    _ => None,
+                })?;
+                let value = value.map(|string| {
+                    let (decimal, integer_digits) = normalize_decimal(&string, precision_scale.map(|(_, scale)| scale as usize))
+                        .ok_or_else(|| self.invalid_format(key, json))?;
+                    match precision_scale {
+                        Some((precision, scale)) if integer_digits > (precision - scale) as usize =>
+                            Err(ConversionError::IntegerOutOfRange { key: key.to_string(), value: string, type_: self.name() }),
+                        _ => Ok(Decimal(decimal)),
+                    }
+                }).transpose()?;
+                unwrap_if_required(key, value, required)
+            }
+            Type::Timestamp => {
+                let value = self.json_to(key, json, |json| json_to_date_time(json, options.format, options.time_zone))?
+                    .transpose()?;
+                let value = match (value, options.clock_skew) {
+                    (Some(date_time), Some(clock_skew)) =>
+                        Some(date_time.checked_add_signed(clock_skew).ok_or(ConversionError::TimestampTooLarge())?),
+                    (value, _) => value,
+                };
+                unwrap_if_required(key, value, required)
+            }
+            Type::Json => {
+                let value = Some(json).filter(|json| !json.is_null());
+                if let (Some(value), Some(max_size)) = (value, options.max_size) {
+                    let size = value.to_string().len();
+                    if size > max_size {
+                        return Err(ConversionError::TooLarge { key: key.to_string(), size, max_size });
+                    }
+                }
+                unwrap_if_required(key, value.cloned(), required)
+            }
+            Type::Array(element) => {
+                // The array itself may be required, but its elements can always be null.
+                let element_options = ConversionOptions { required: false, ..*options };
+                let value = self.json_to(key, json, Value::as_array)?
+                    .map(|elements| elements.iter().enumerate()
+                        .map(|(index, json)| element.json_to_sql(&format!("{}[{}]", key, index), json, &element_options))
+                        .collect::<Result<Vec<_>, _>>()
+                        .map(Array))
+                    .transpose()?;
+                unwrap_if_required(key, value, required)
+            }
+        }?;
+        // After the conversion, so that values of the wrong type are reported as such.
+        if let Some(rules) = options.rules {
+            if !json.is_null() && !matches!(self, Type::Array(_)) {
+                rules.check(key, json, |json| self.normalize(json, options))?;
+            }
+        }
+        Ok(value)
+    }
+
+    /// Converts a JSON value to the JSON type expected for this type, if that is unambiguous.
+    /// Other values are returned unchanged, so they produce the usual error.
+    fn coerce<'a>(&self, json: &'a Value, options: &ConversionOptions) -> Cow<'a, Value> {
+        let epoch_format = options.format.is_some_and(|format| format.units_per_second().is_some());
+        let coerced = match (self, json) {
+            (Type::Bool, Value::Number(number)) => match number.as_u64() {
+                Some(0) => Some(Value::Bool(false)),
+                Some(1) => Some(Value::Bool(true)),
+                _ => None,
+            },
+            (Type::Bool, Value::String(string)) => string.parse::<bool>().ok().map(Value::Bool),
+            (Type::I32 | Type::I64 | Type::F32 | Type::F64, Value::String(string)) =>
+                string.trim().parse::<serde_json::Number>().ok().map(Value::Number),
+            (Type::Timestamp, Value::String(string)) if epoch_format =>
+                string.trim().parse::<serde_json::Number>().ok().map(Value::Number),
+            (Type::String | Type::Text, Value::Number(number)) => Some(Value::String(number.to_string())),
+            _ => None,
+        };
+        coerced.map_or(Cow::Borrowed(json), Cow::Owned)
+    }
+
+    /// Returns a value that `json_to_sql` accepts in its canonical form: coerced, with floats as
+    /// numbers, decimals as strings, UUIDs hyphenated and timestamps as RFC 3339 strings, which Postgres
+    /// can cast regardless of the timestamp format. Values that do not convert are returned unchanged.
+    pub fn normalize(&self, json: &Value, options: &ConversionOptions) -> Value {
+        let json = if options.coerce { self.coerce(json, options) } else { Cow::Borrowed(json) };
+        let normalized = match (self, json.as_ref()) {
+            (Type::F32 | Type::F64, Value::Number(number)) => number.as_f64().and_then(Number::from_f64).map(Value::Number),
+            (Type::Numeric(precision_scale), Value::Number(_) | Value::String(_)) => {
+                let string = json.as_str().map_or_else(|| json.to_string(), |string| string.trim().to_string());
+                // Without trailing zeros, which do not change the value.
+                normalize_decimal(&string, precision_scale.map(|(_, scale)| scale as usize))
+                    .map(|(decimal, _)| match decimal.contains('.') {
+                        true => decimal.trim_end_matches('0').trim_end_matches('.').to_string(),
+                        false => decimal,
+                    })
+                    .map(|decimal| Value::String(if decimal == "-0" { "0".to_string() } else { decimal }))
+            }
+            (Type::Uuid, Value::String(string)) => Uuid::parse(string).map(|uuid| Value::String(uuid.to_string())),
+            (Type::Timestamp, json) => json_to_date_time(json, options.format, options.time_zone)
+                .and_then(Result::ok)
+                .map(|date_time| Value::String(date_time.to_rfc3339())),
+            (Type::Array(element), Value::Array(elements)) =>
+                Some(Value::Array(elements.iter().map(|json| element.normalize(json, options)).collect())),
+            _ => None,
+        };
+        normalized.unwrap_or_else(|| json.into_owned())
+    }
+
+    /// Applies `convert` to non-null values, which must return `None` if the value has the wrong type.
+    fn json_to<'a, T>(&self, key: &str, json: &'a Value, convert: impl FnOnce(&'a Value) -> Option<T>) -> Result<Option<T>, ConversionError> {
+        if json.is_null() {
+            return Ok(None);
+        }
+        convert(json).map(Some).ok_or_else(|| ConversionError::WrongType {
+            key: key.to_string(),
+            expected: self.name(),
+            actual: json_type_name(json),
+        })
+    }
+
+    fn invalid_format(&self, key: &str, json: &Value) -> ConversionError {
+        ConversionError::InvalidFormat { key: key.to_string(), value: json.to_string(), type_: self.name() }
+    }
+
+    fn json_to_int<T: TryFrom<i64>>(&self, key: &str, json: &Value) -> Result<Option<T>, ConversionError> {
+        // Integers above i64::MAX are still integers, they are just out of range.
+        let value = self.json_to(key, json, |json| json.as_i64().map(Some).or_else(|| json.is_u64().then_some(None)))?;
+        match value {
+            Some(i) => i.and_then(|i| T::try_from(i).ok()).map(Some)
+                .ok_or_else(|| ConversionError::IntegerOutOfRange { key: key.to_string(), value: json.to_string(), type_: self.name() }),
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Uuid([u8; 16]);
+
+impl Uuid {
+    /// Generates a random (version 4) UUID.
+    pub fn new_v4() -> Uuid {
+        let mut bytes = rand::random::<[u8; 16]>();
+        bytes[6] = (bytes[6] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+        Uuid(bytes)
+    }
+
+    /// Parses the hyphenated form `a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11` or the same without hyphens.
+    fn parse(string: &str) -> Option<Uuid> {
+        let hyphenated = string.len() == 36 && [8, 13, 18, 23].iter().all(|&i| string.as_bytes()[i] == b'-');
+        let hex = if hyphenated { string.replace('-', "") } else { string.to_string() };
+        if hex.len() != 32 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        decode_hex(&hex)?.try_into().ok().map(Uuid)
+    }
+}
+
+impl Display for Uuid {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        for (index, byte) in self.0.iter().enumerate() {
+            if [4, 6, 8, 10].contains(&index) {
+                f.write_str("-")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl ToSql for Uuid {
+    fn to_sql(&self, _ty: &postgres::types::Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        out.extend_from_slice(&self.0);
+        Ok(IsNull::No)
+    }
+
+    accepts!(UUID);
+    to_sql_checked!();
+}
+
+/// A decimal number, as returned by `normalize_decimal`.
+#[derive(Debug)]
+struct Decimal(String);
+
+impl ToSql for Decimal {
+    /// The binary format consists of the number of base 10000 digits, the weight of the first
+    /// digit, the sign, the number of decimal digits after the decimal point, and the digits.
+    fn to_sql(&self, _ty: &postgres::types::Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        let (negative, decimal) = match self.0.strip_prefix('-') {
+            Some(decimal) => (true, decimal),
+            None => (false, self.0.as_str()),
+        };
+        let (integer, fraction) = decimal.split_once('.').unwrap_or((decimal, ""));
+        // Pad both parts to whole base 10000 digits.
+        let integer = format!("{}{}", "0".repeat((4 - integer.len() % 4) % 4), integer);
+        let fraction = format!("{}{}", fraction, "0".repeat((4 - fraction.len() % 4) % 4));
+        let mut digits = integer.as_bytes().chunks(4).chain(fraction.as_bytes().chunks(4))
+            .map(|chunk| std::str::from_utf8(chunk).unwrap().parse::<i16>().unwrap())
+            .collect::<Vec<_>>();
+        let mut weight = (integer.len() / 4) as i16 - 1;
+        let leading_zeros = digits.iter().take_while(|&&digit| digit == 0).count();
+        digits.drain(..leading_zeros);
+        weight -= leading_zeros as i16;
+        let trailing_zeros = digits.iter().rev().take_while(|&&digit| digit == 0).count();
+        digits.truncate(digits.len() - trailing_zeros);
+        if digits.is_empty() {
+            weight = 0;
+        }
+
+        out.extend_from_slice(&(digits.len() as i16).to_be_bytes());
+        out.extend_from_slice(&weight.to_be_bytes());
+        out.extend_from_slice(&(if negative && !digits.is_empty() { 0x4000_u16 } else { 0 }).to_be_bytes());
+        out.extend_from_slice(&(decimal.split_once('.').map_or(0, |(_, fraction)| fraction.len()) as u16).to_be_bytes());
+        for digit in digits {
+            out.extend_from_slice(&digit.to_be_bytes());
         }
+        Ok(IsNull::No)
+    }
+
+    accepts!(NUMERIC);
+    to_sql_checked!();
+}
+
+/// A one-dimensional array of converted values.
+#[derive(Debug)]
+struct Array(Vec<Box<dyn ToSql + Sync>>);
+
+impl ToSql for Array {
+    fn to_sql(&self, ty: &postgres::types::Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        let element_type = match ty.kind() {
+            Kind::Array(element_type) => element_type,
+            _ => unreachable!("accepts() only accepts arrays"),
+        };
+        let dimension = ArrayDimension { len: i32::try_from(self.0.len())?, lower_bound: 1 };
+        array_to_sql(Some(dimension), element_type.oid(), &self.0, |element, out| {
+            match element.to_sql_checked(element_type, out)? {
+                IsNull::Yes => Ok(postgres_protocol::IsNull::Yes),
+                IsNull::No => Ok(postgres_protocol::IsNull::No),
+            }
+        }, out)?;
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &postgres::types::Type) -> bool {
+        matches!(ty.kind(), Kind::Array(_))
+    }
+
+    to_sql_checked!();
+}
+
+/// Parses a decimal number like `-1.25e3` and, if a scale is given, rounds it to that many digits
+/// after the decimal point, like Postgres does. Returns the number without exponent and the number
+/// of significant digits before the decimal point.
+fn normalize_decimal(string: &str, scale: Option<usize>) -> Option<(String, usize)> {
+    let (mantissa, exponent) = match string.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, exponent.parse::<i64>().ok()?),
+        None => (string, 0),
+    };
+    // Larger exponents do not fit into Postgres numerics anyway.
+    if exponent.abs() > MAX_NUMERIC_PRECISION as i64 {
+        return None;
+    }
+    let (negative, mantissa) = match mantissa.strip_prefix('-') {
+        Some(mantissa) => (true, mantissa),
+        None => (false, mantissa.strip_prefix('+').unwrap_or(mantissa)),
+    };
+    let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if integer.is_empty() && fraction.is_empty() || !integer.bytes().chain(fraction.bytes()).all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let mut digits = integer.bytes().chain(fraction.bytes()).map(|b| b - b'0').collect::<Vec<u8>>();
+    // Position of the decimal point in digits, after applying the exponent.
+    let mut point = integer.len() as i64 + exponent;
+    if point < 0 {
+        digits.splice(0..0, std::iter::repeat_n(0, -point as usize));
+        point = 0;
+    }
+    let mut point = point as usize;
+    if digits.len() < point {
+        digits.resize(point, 0);
+    }
+    if let Some(scale) = scale {
+        if digits.len() > point + scale {
+            let round_up = digits[point + scale] >= 5;
+            digits.truncate(point + scale);
+            if round_up {
+                match digits.iter().rposition(|&digit| digit != 9) {
+                    Some(position) => {
+                        digits[position] += 1;
+                        digits[position + 1..].iter_mut().for_each(|digit| *digit = 0);
+                    }
+                    None => {
+                        digits.iter_mut().for_each(|digit| *digit = 0);
+                        digits.insert(0, 1);
+                        point += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    let integer_digits = digits[..point].iter().skip_while(|&&digit| digit == 0).count();
+    let to_string = |digits: &[u8]| digits.iter().map(|digit| (b'0' + digit) as char).collect::<String>();
+    let mut decimal = if negative { "-".to_string() } else { String::new() };
+    decimal += &to_string(&digits[point - integer_digits..point]);
+    if integer_digits == 0 {
+        decimal.push('0');
     }
+    if digits.len() > point {
+        decimal.push('.');
+        decimal += &to_string(&digits[point..]);
+    }
+    Some((decimal, integer_digits))
 }
 
-pub fn header_to_sql<'a>(key: &str, value: Option<&'a str>, required: bool) -> Result<Box<dyn ToSql + Sync + 'a>, ConversionError> {
-    unwrap_if_required(key, value, required)
+/// Describes the type of a JSON value for error messages.
+fn json_type_name(json: &Value) -> &'static str {
+    match json {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(number) if number.is_f64() => "a floating-point number",
+        Value::Number(_) => "an integer",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
 }
 
 pub fn unwrap_if_required<'a, T>(key: &str, option: Option<T>, required: bool) -> Result<Box<dyn ToSql + Sync + 'a>, ConversionError>
@@ -93,20 +824,293 @@ pub fn unwrap_if_required<'a, T>(key: &str, option: Option<T>, required: bool) -
     }
 }
 
-fn json_to_date_time(json: &serde_json::Value) -> Result<Option<DateTime<FixedOffset>>, ConversionError> {
-    if json.is_number() {
-        let timestamp = json.as_f64().unwrap();
-        let naive = NaiveDateTime::from_timestamp_opt(timestamp.floor() as i64, (1e9 * timestamp.fract()) as u32);
-        let offset = FixedOffset::west_opt(0).unwrap();
-        if naive.is_none() {
-            Err(ConversionError::TimestampTooLarge())
-        } else {
-            Ok(Some(TimeZone::from_utc_datetime(&offset, &naive.unwrap())))
+/// Parses a timestamp that is not in a column, in the given format, or if none is given, as seconds
+/// since the epoch or an RFC 3339 string.
+pub fn parse_timestamp(key: &str, json: &Value, format: Option<&TimestampFormat>) -> Result<DateTime<FixedOffset>, ConversionError> {
+    json_to_date_time(json, format, None).unwrap_or_else(|| Err(ConversionError::WrongType {
+        key: key.to_string(),
+        expected: Type::Timestamp.name(),
+        actual: json_type_name(json),
+    }))
+}
+
+/// Parses a timestamp in the given format, or if none is given, a number of seconds since the epoch
+/// or an RFC 3339 string. Returns `None` for JSON types that do not fit the format.
+fn json_to_date_time(json: &Value, format: Option<&TimestampFormat>, time_zone: Option<Zone>)
+    -> Option<Result<DateTime<FixedOffset>, ConversionError>>
+{
+    match (format, json) {
+        (None, Value::Number(_)) => epoch_to_date_time(json, 1),
+        (Some(format), Value::Number(_)) => epoch_to_date_time(json, format.units_per_second()?),
+        (None | Some(TimestampFormat::Rfc3339), Value::String(string)) =>
+            Some(DateTime::parse_from_rfc3339(string).or_else(|err| match time_zone {
+                // Like RFC 3339, but without the offset.
+                Some(time_zone) => NaiveDateTime::parse_from_str(string, "%Y-%m-%dT%H:%M:%S%.f")
+                    .map_err(|_| ConversionError::TimestampFormat(err))
+                    .and_then(|naive| time_zone.localize(&naive)),
+                None => Err(ConversionError::TimestampFormat(err)),
+            })),
+        (Some(TimestampFormat::Rfc2822), Value::String(string)) =>
+            Some(DateTime::parse_from_rfc2822(string).map_err(ConversionError::TimestampFormat)),
+        (Some(TimestampFormat::Strftime(format)), Value::String(string)) =>
+            Some(parse_strftime(string, format, time_zone.unwrap_or_default())),
+        _ => None,
+    }
+}
+
+fn epoch_to_date_time(json: &Value, units_per_second: i64) -> Option<Result<DateTime<FixedOffset>, ConversionError>> {
+    // Integers are split exactly; floats saturate when cast, so huge values end up out of range.
+    let (seconds, nanoseconds) = match json.as_i64() {
+        Some(units) => (units.div_euclid(units_per_second), units.rem_euclid(units_per_second) * (1_000_000_000 / units_per_second)),
+        None => {
+            let timestamp = json.as_f64()? / units_per_second as f64;
+            let seconds = timestamp.floor();
+            (seconds as i64, (1e9 * (timestamp - seconds)) as i64)
         }
-    } else if json.is_string() {
-        Ok(Some(DateTime::parse_from_rfc3339(json.as_str().unwrap())
-            .map_err(|err| ConversionError::TimestampFormat(err))?))
-    } else {
-        Ok(None)
+    };
+    Some(NaiveDateTime::from_timestamp_opt(seconds, nanoseconds as u32)
+        .map(|naive| Utc.from_utc_datetime(&naive).fixed_offset())
+        .ok_or(ConversionError::TimestampTooLarge()))
+}
+
+fn parse_strftime(string: &str, format: &str, time_zone: Zone) -> Result<DateTime<FixedOffset>, ConversionError> {
+    let mut parsed = Parsed::new();
+    chrono::format::parse(&mut parsed, string, StrftimeItems::new(format))
+        .map_err(ConversionError::TimestampFormat)?;
+    if parsed.offset.is_some() {
+        return parsed.to_datetime().map_err(ConversionError::TimestampFormat);
+    }
+    // Formats with only a date mean midnight.
+    if parsed.hour_mod_12.is_none() && parsed.timestamp.is_none() {
+        parsed.set_hour(0).and_then(|_| parsed.set_minute(0)).map_err(ConversionError::TimestampFormat)?;
+    }
+    let naive = parsed.to_naive_datetime_with_offset(0).map_err(ConversionError::TimestampFormat)?;
+    time_zone.localize(&naive)
+}
+
+#[test]
+fn present_values_must_match_type() {
+    use serde_json::json;
+
+    let options = ConversionOptions { required: true, ..ConversionOptions::default() };
+    let error = |type_: Type, json: Value| type_.json_to_sql("x", &json, &options).err();
+    assert_eq!(error(Type::I32, json!(42)), None);
+    assert_eq!(error(Type::I32, json!(null)), Some(ConversionError::MissingValue("x".to_string())));
+    assert_eq!(error(Type::I32, json!("42")),
+        Some(ConversionError::WrongType { key: "x".to_string(), expected: "i32", actual: "a string" }));
+    assert_eq!(error(Type::I64, json!(1.5)),
+        Some(ConversionError::WrongType { key: "x".to_string(), expected: "i64", actual: "a floating-point number" }));
+    assert_eq!(error(Type::I32, json!(1u64 << 40)),
+        Some(ConversionError::IntegerOutOfRange { key: "x".to_string(), value: "1099511627776".to_string(), type_: "i32" }));
+    assert_eq!(error(Type::I64, json!(u64::MAX)),
+        Some(ConversionError::IntegerOutOfRange { key: "x".to_string(), value: u64::MAX.to_string(), type_: "i64" }));
+    assert_eq!(error(Type::F32, json!(1e300)),
+        Some(ConversionError::NonFiniteFloat { key: "x".to_string(), value: "1e300".to_string(), type_: "f32" }));
+    assert_eq!(error(Type::F64, json!(1)), None);
+    assert_eq!(error(Type::Timestamp, json!(true)),
+        Some(ConversionError::WrongType { key: "x".to_string(), expected: "timestamp", actual: "a boolean" }));
+}
+
+#[test]
+fn coerce_unambiguous_values() {
+    use serde_json::json;
+
+    let coerce = |type_: Type, json: Value| type_.coerce(&json, &ConversionOptions::default()).into_owned();
+    assert_eq!(coerce(Type::I32, json!(" 42 ")), json!(42));
+    assert_eq!(coerce(Type::F64, json!("1.5")), json!(1.5));
+    assert_eq!(coerce(Type::I32, json!("forty-two")), json!("forty-two"));
+    assert_eq!(coerce(Type::Bool, json!(1)), json!(true));
+    assert_eq!(coerce(Type::Bool, json!("false")), json!(false));
+    assert_eq!(coerce(Type::Bool, json!(2)), json!(2));
+    assert_eq!(coerce(Type::String, json!(7)), json!("7"));
+    assert_eq!(coerce(Type::Timestamp, json!("7")), json!("7"));
+}
+
+#[test]
+fn parse_timestamp_formats() {
+    use serde_json::json;
+
+    let parse = |json: Value, format: Option<&str>, time_zone: Option<Zone>| {
+        let format = format.map(|format| TimestampFormat::try_from(format.to_string()).unwrap());
+        json_to_date_time(&json, format.as_ref(), time_zone).map(|result| result.unwrap().to_rfc3339())
+    };
+    let plus_two = Zone::Fixed(FixedOffset::east_opt(7200).unwrap());
+    assert_eq!(parse(json!(1554130180.5), None, None).unwrap(), "2019-04-01T14:49:40.500+00:00");
+    assert_eq!(parse(json!(-0.5), None, None).unwrap(), "1969-12-31T23:59:59.500+00:00");
+    assert_eq!(parse(json!(1554130180123_i64), Some("epoch_ms"), None).unwrap(), "2019-04-01T14:49:40.123+00:00");
+    assert_eq!(parse(json!(1554130180000001_i64), Some("epoch_us"), None).unwrap(), "2019-04-01T14:49:40.000001+00:00");
+    assert_eq!(parse(json!("2019-04-01T14:49:40+02:00"), None, None).unwrap(), "2019-04-01T14:49:40+02:00");
+    assert_eq!(parse(json!("2019-04-01T14:49:40"), None, Some(plus_two)).unwrap(), "2019-04-01T14:49:40+02:00");
+    assert_eq!(parse(json!("Mon, 01 Apr 2019 14:49:40 +0200"), Some("rfc2822"), None).unwrap(), "2019-04-01T14:49:40+02:00");
+    assert_eq!(parse(json!("01.04.2019 14:49"), Some("%d.%m.%Y %H:%M"), None).unwrap(), "2019-04-01T14:49:00+00:00");
+    assert_eq!(parse(json!("01.04.2019"), Some("%d.%m.%Y"), Some(plus_two)).unwrap(), "2019-04-01T00:00:00+02:00");
+    assert_eq!(parse(json!("2019-04-01T14:49:40"), Some("epoch_s"), None), None);
+    assert!(json_to_date_time(&json!("2019-04-01T14:49:40"), None, None).unwrap().is_err());
+    assert!(TimestampFormat::try_from("%Q".to_string()).is_err());
+}
+
+#[test]
+fn correct_clock_skew() {
+    use serde_json::json;
+
+    let encode = |value: &dyn ToSql| {
+        let mut out = BytesMut::new();
+        value.to_sql_checked(&postgres::types::Type::TIMESTAMPTZ, &mut out).unwrap();
+        out.to_vec()
+    };
+    let options = |clock_skew| ConversionOptions { clock_skew: Some(clock_skew), ..ConversionOptions::default() };
+    let corrected = Type::Timestamp.json_to_sql("t", &json!(1554130180), &options(Duration::seconds(-90))).unwrap();
+    assert_eq!(encode(corrected.as_ref()), encode(&DateTime::parse_from_rfc3339("2019-04-01T14:48:10+00:00").unwrap()));
+    assert!(Type::Timestamp.json_to_sql("t", &Value::Null, &options(Duration::seconds(-90))).is_ok());
+    assert_eq!(Type::Timestamp.json_to_sql("t", &json!(1554130180), &options(Duration::days(100_000_000))).err().map(|err| err.code()),
+        Some("bad_timestamp"));
+
+    assert_eq!(parse_timestamp("sent_at", &json!(1554130180), None).unwrap().to_rfc3339(), "2019-04-01T14:49:40+00:00");
+    assert_eq!(parse_timestamp("sent_at", &json!("2019-04-01T14:49:40+02:00"), None).unwrap().to_rfc3339(), "2019-04-01T14:49:40+02:00");
+    assert_eq!(parse_timestamp("sent_at", &json!(true), None).err().map(|err| err.code()), Some("wrong_type"));
+    assert_eq!(parse_timestamp("sent_at", &json!(1e20), None).err().map(|err| err.code()), Some("bad_timestamp"));
+}
+
+#[test]
+fn json_values_are_limited_in_size() {
+    use serde_json::json;
+
+    let options = ConversionOptions { max_size: Some(16), ..ConversionOptions::default() };
+    let error = |json: Value| Type::Json.json_to_sql("x", &json, &options).err();
+    assert_eq!(error(json!({"os": "linux"})), None);
+    assert_eq!(error(json!(null)), None);
+    assert_eq!(error(json!({"os": "linux", "version": 6})),
+        Some(ConversionError::TooLarge { key: "x".to_string(), size: 26, max_size: 16 }));
+}
+
+#[test]
+fn parse_column_types() {
+    assert_eq!(Type::try_from("uuid".to_string()), Ok(Type::Uuid));
+    assert_eq!(Type::try_from("numeric".to_string()), Ok(Type::Numeric(None)));
+    assert_eq!(Type::try_from("numeric(10, 2)".to_string()), Ok(Type::Numeric(Some((10, 2)))));
+    assert_eq!(Type::try_from("numeric(5)".to_string()), Ok(Type::Numeric(Some((5, 0)))));
+    assert!(Type::try_from("numeric(2,3)".to_string()).is_err());
+    assert!(Type::try_from("varchar".to_string()).is_err());
+    assert_eq!(Type::Numeric(Some((10, 2))).postgres_type_name(), "numeric(10,2)");
+    assert_eq!(serde_yaml::from_str::<Type>("[i64]").unwrap(), Type::Array(Box::new(Type::I64)));
+    assert_eq!(serde_yaml::from_str::<Type>("'[numeric(4,1)]'").unwrap(), Type::Array(Box::new(Type::Numeric(Some((4, 1))))));
+    assert!(serde_yaml::from_str::<Type>("[[i64]]").is_err());
+    assert!(serde_yaml::from_str::<Type>("[i64, i32]").is_err());
+    assert_eq!(Type::Array(Box::new(Type::Numeric(Some((4, 1))))).postgres_type_name(), "numeric(4,1)[]");
+}
+
+#[test]
+fn normalize_decimals() {
+    let normalize = |string: &str, scale: Option<usize>| normalize_decimal(string, scale);
+    assert_eq!(normalize("19.99", Some(2)), Some(("19.99".to_string(), 2)));
+    assert_eq!(normalize("-0.005", Some(2)), Some(("-0.01".to_string(), 0)));
+    assert_eq!(normalize("9.995", Some(2)), Some(("10.00".to_string(), 2)));
+    assert_eq!(normalize("1.25e3", Some(0)), Some(("1250".to_string(), 4)));
+    assert_eq!(normalize("125e-4", None), Some(("0.0125".to_string(), 0)));
+    assert_eq!(normalize("007", None), Some(("7".to_string(), 1)));
+    assert_eq!(normalize("1e99999", None), None);
+    assert_eq!(normalize("NaN", None), None);
+    assert_eq!(normalize(".", None), None);
+    assert!(Uuid::parse("A0EEBC99-9C0B-4EF8-BB6D-6BB9BD380A11").is_some());
+    assert!(Uuid::parse("a0eebc999c0b4ef8bb6d6bb9bd380a11").is_some());
+    assert!(Uuid::parse("a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a1").is_none());
+    assert!(Uuid::parse("+0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11").is_none());
+}
+
+#[test]
+fn encode_numerics_and_uuids() {
+    let encode = |value: &dyn ToSql, ty: &postgres::types::Type| {
+        let mut out = BytesMut::new();
+        value.to_sql_checked(ty, &mut out).unwrap();
+        out.to_vec()
+    };
+    // Number of digits, weight, sign and scale, followed by the base 10000 digits, all 16 bits.
+    let numeric = |decimal: &str| encode(&Decimal(decimal.to_string()), &postgres::types::Type::NUMERIC)
+        .chunks(2)
+        .map(|chunk| i16::from_be_bytes([chunk[0], chunk[1]]))
+        .collect::<Vec<_>>();
+    assert_eq!(numeric("0"), vec![0, 0, 0, 0]);
+    assert_eq!(numeric("-0.00"), vec![0, 0, 0, 2]);
+    assert_eq!(numeric("-0.5"), vec![1, -1, 0x4000, 1, 5000]);
+    assert_eq!(numeric("12345.6789"), vec![3, 1, 0, 4, 1, 2345, 6789]);
+    assert_eq!(numeric("10000"), vec![1, 1, 0, 0, 1]);
+    assert_eq!(numeric(&normalize_decimal("1e-20", None).unwrap().0), vec![1, -5, 0, 20, 1]);
+
+    let uuid = Uuid::parse("a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11").unwrap();
+    assert_eq!(encode(&uuid, &postgres::types::Type::UUID), decode_hex("a0eebc999c0b4ef8bb6d6bb9bd380a11").unwrap());
+}
+
+/// Needs a database, so it only runs if `ATTOLYTICS_TEST_DATABASE_URL` is set.
+#[test]
+fn postgres_decodes_numerics_and_uuids() {
+    let db_url = match std::env::var("ATTOLYTICS_TEST_DATABASE_URL") {
+        Ok(db_url) => db_url,
+        Err(_) => return,
+    };
+    let mut client = postgres::Client::connect(&db_url, postgres::NoTls).unwrap();
+    for (decimal, expected) in [
+        ("0", "0"),
+        ("-0.00", "0.00"),
+        ("-0.5", "-0.5"),
+        ("12345.6789", "12345.6789"),
+        ("10000", "10000"),
+        ("0.00000000000000000001", "0.00000000000000000001"),
+        ("-98765432109876543210.0123", "-98765432109876543210.0123"),
+    ] {
+        let row = client.query_one("SELECT $1::numeric::text", &[&Decimal(decimal.to_string())]).unwrap();
+        assert_eq!(row.get::<_, String>(0), expected);
     }
+    let row = client.query_one("SELECT $1::uuid::text", &[&Uuid::parse("A0EEBC99-9C0B-4EF8-BB6D-6BB9BD380A11").unwrap()]).unwrap();
+    assert_eq!(row.get::<_, String>(0), "a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11");
+}
+
+#[test]
+fn array_elements_must_match_type() {
+    use serde_json::json;
+
+    let type_ = Type::Array(Box::new(Type::I32));
+    let error = |json: Value| type_.json_to_sql("x", &json, &ConversionOptions::default()).err();
+    assert_eq!(error(json!([1, null, 3])), None);
+    assert_eq!(error(json!([1, "2"])),
+        Some(ConversionError::WrongType { key: "x[1]".to_string(), expected: "i32", actual: "a string" }));
+    assert_eq!(error(json!(1)),
+        Some(ConversionError::WrongType { key: "x".to_string(), expected: "array", actual: "an integer" }));
+}
+
+#[test]
+fn values_must_satisfy_rules() {
+    use serde_json::json;
+
+    let rules = serde_yaml::from_str::<Rules>(r#"
+        min: 0
+        max: 10.5
+        pattern: "[a-z]+"
+        max_length: 3
+        "#).unwrap();
+    let options = ConversionOptions { rules: Some(&rules), coerce: true, ..ConversionOptions::default() };
+    let error = |type_: Type, json: Value| type_.json_to_sql("x", &json, &options).err().map(|err| err.to_string());
+    assert_eq!(error(Type::I32, json!(10)), None);
+    assert_eq!(error(Type::I32, json!("-1")), Some("value \"x\" violates rule min 0: -1".to_string()));
+    assert_eq!(error(Type::F64, json!(10.6)), Some("value \"x\" violates rule max 10.5: 10.6".to_string()));
+    assert_eq!(error(Type::String, json!("abc")), None);
+    assert_eq!(error(Type::String, json!("ab1")), Some("value \"x\" violates rule pattern [a-z]+: \"ab1\"".to_string()));
+    assert_eq!(error(Type::String, json!("abcd")), Some("value \"x\" violates rule max_length 3: \"abcd\"".to_string()));
+    assert_eq!(error(Type::Array(Box::new(Type::String)), json!(["a", "B"])),
+        Some("value \"x[1]\" violates rule pattern [a-z]+: \"B\"".to_string()));
+
+    let rules = serde_yaml::from_str::<Rules>("enum: [ios, android]").unwrap();
+    let options = ConversionOptions { rules: Some(&rules), ..ConversionOptions::default() };
+    assert!(Type::String.json_to_sql("x", &json!("ios"), &options).is_ok());
+    assert_eq!(Type::String.json_to_sql("x", &json!("web"), &options).err().map(|err| err.to_string()),
+        Some("value \"x\" violates rule enum [\"ios\",\"android\"]: \"web\"".to_string()));
+    assert_eq!(Type::String.json_to_sql("x", &json!(1), &options).err().map(|err| err.code()), Some("wrong_type"));
+
+    // Allowed values are compared after conversion.
+    let rules = serde_yaml::from_str::<Rules>("enum: [1, 2.5]").unwrap();
+    let options = ConversionOptions { rules: Some(&rules), coerce: true, ..ConversionOptions::default() };
+    assert!(Type::F64.json_to_sql("x", &json!(1.0), &options).is_ok());
+    assert!(Type::F64.json_to_sql("x", &json!("2.5"), &options).is_ok());
+    assert!(Type::Numeric(None).json_to_sql("x", &json!("1.00"), &options).is_ok());
+    assert!(Type::Array(Box::new(Type::F64)).json_to_sql("x", &json!([1, null]), &options).is_ok());
+    assert_eq!(Type::F64.json_to_sql("x", &json!(2), &options).err().map(|err| err.code()), Some("rule_violation"));
 }